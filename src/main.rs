@@ -3,7 +3,7 @@
 use std::fs::{self, File as FileSystem};
 use std::io;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 use walkdir::WalkDir;
 use rayon::prelude::*;
@@ -11,8 +11,12 @@ use blake3;
 use fastcdc::v2020::FastCDC;
 use memmap2::Mmap;
 use fs2::FileExt;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH, Duration};
 use clap::Parser;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::os::unix::fs::FileTypeExt;
+use clap::ValueEnum;
+use zstd;
 
 #[cfg(feature = "ai_priority")]
 use tch::{CModule, Tensor};
@@ -45,6 +49,31 @@ struct Args {
     /// Set verbosity level (-v for progress, -vv for detailed per-file output)
     #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
     verbosity: u8,
+
+    /// Gitignore-style glob pattern to exclude from scanning; repeatable.
+    /// Patterns are also read from a `.difftoolignore` file at the scan root.
+    #[arg(long = "ignore")]
+    ignore: Vec<String>,
+
+    /// Number of hashing threads to use. Defaults to
+    /// min(available parallelism, 16), since beyond that disk I/O is
+    /// typically the bottleneck rather than CPU.
+    #[arg(long)]
+    jobs: Option<usize>,
+
+    /// On-disk state format. If unset, inferred from the state file's
+    /// extension (`.state`/`.bin` means binary, anything else means json),
+    /// defaulting to json when there's no extension at all.
+    #[arg(long, value_enum)]
+    format: Option<StateFormat>,
+}
+
+/// On-disk encoding for the saved state: human-readable JSON, or the
+/// compact zstd-compressed binary format.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StateFormat {
+    Json,
+    Binary,
 }
 
 /// Represents a file or directory's hashed state for comparison between runs.
@@ -53,7 +82,297 @@ struct Args {
 struct Fingerprint {
     relative_path: PathBuf,
     is_dir: bool,
-    chunks: Option<Vec<String>>
+    chunks: Option<Vec<String>>,
+    /// File length in bytes, recorded alongside `mtime` so unchanged files can
+    /// skip re-hashing on the next run. `None` for directories.
+    size: Option<u64>,
+    /// Last-modified time as (seconds, nanoseconds) since the Unix epoch.
+    mtime: Option<(u64, u32)>,
+    /// For symlinks, the link target as recorded (never dereferenced); a
+    /// change here surfaces as a `copy` op so retargeting is picked up.
+    link_target: Option<String>,
+}
+
+/// Special file types `DiffTool` won't read the contents of. Symlinks are
+/// still tracked by their link target; the rest are just skipped.
+#[derive(Debug, Clone, Copy)]
+enum BadType {
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Symlink,
+    Unknown,
+}
+
+impl BadType {
+    fn label(&self) -> &'static str {
+        match self {
+            BadType::CharacterDevice => "character device",
+            BadType::BlockDevice => "block device",
+            BadType::Fifo => "FIFO",
+            BadType::Socket => "socket",
+            BadType::Symlink => "symlink",
+            BadType::Unknown => "unknown special file",
+        }
+    }
+}
+
+/// Classifies a non-regular, non-directory file type encountered during traversal.
+fn classify_bad_type(file_type: std::fs::FileType) -> BadType {
+    if file_type.is_symlink() {
+        BadType::Symlink
+    } else if file_type.is_char_device() {
+        BadType::CharacterDevice
+    } else if file_type.is_block_device() {
+        BadType::BlockDevice
+    } else if file_type.is_fifo() {
+        BadType::Fifo
+    } else if file_type.is_socket() {
+        BadType::Socket
+    } else {
+        BadType::Unknown
+    }
+}
+
+/// The persisted state: fingerprints from the last run plus the wall-clock
+/// time they were saved at, used to detect ambiguous mtimes.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    saved_at: (u64, u32),
+    fingerprints: Vec<Fingerprint>,
+}
+
+/// Converts a filesystem `SystemTime` into a (seconds, nanoseconds) pair
+/// since the Unix epoch, for cheap storage and comparison.
+fn system_time_to_parts(time: SystemTime) -> (u64, u32) {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    (duration.as_secs(), duration.subsec_nanos())
+}
+
+const BINARY_MAGIC: &[u8; 4] = b"DTS1";
+const BINARY_VERSION: u32 = 1;
+const BINARY_HEADER_SIZE: usize = 32;
+const BINARY_RECORD_SIZE: usize = 57;
+
+const BINARY_FLAG_IS_DIR: u8 = 0b0001;
+const BINARY_FLAG_HAS_FILE_META: u8 = 0b0010;
+const BINARY_FLAG_HAS_CHUNKS: u8 = 0b0100;
+const BINARY_FLAG_HAS_LINK_TARGET: u8 = 0b1000;
+
+/// Encodes a `SavedState` into a fixed-size header, one fixed-size record
+/// per fingerprint, and a trailing blob of path/chunk/link-target bytes that
+/// records point into by offset and length. Not compressed; the caller is
+/// expected to zstd-compress the result before writing it to disk. Fails if
+/// a fingerprint's chunks aren't valid BLAKE3 hex (e.g. a stale or
+/// hand-edited state file), rather than panicking on malformed input.
+fn encode_binary_state(saved_state: &SavedState) -> Result<Vec<u8>, String> {
+    let mut records = Vec::with_capacity(saved_state.fingerprints.len() * BINARY_RECORD_SIZE);
+    let mut blob = Vec::new();
+
+    for fingerprint in &saved_state.fingerprints {
+        let mut flags = 0u8;
+        if fingerprint.is_dir { flags |= BINARY_FLAG_IS_DIR; }
+
+        let path_bytes = fingerprint.relative_path.to_string_lossy().into_owned().into_bytes();
+        let path_offset = blob.len() as u64;
+        let path_len = path_bytes.len() as u32;
+        blob.extend_from_slice(&path_bytes);
+
+        let (size, mtime_secs, mtime_nanos) = match (fingerprint.size, fingerprint.mtime) {
+            (Some(size), Some((secs, nanos))) => {
+                flags |= BINARY_FLAG_HAS_FILE_META;
+                (size, secs, nanos)
+            }
+            _ => (0, 0, 0),
+        };
+
+        let (chunks_offset, chunks_count) = match &fingerprint.chunks {
+            Some(chunks) => {
+                flags |= BINARY_FLAG_HAS_CHUNKS;
+                let offset = blob.len() as u64;
+                for chunk in chunks {
+                    let digest = blake3::Hash::from_hex(chunk)
+                        .map_err(|e| format!("invalid chunk hash '{}' for '{}': {}", chunk, fingerprint.relative_path.display(), e))?;
+                    blob.extend_from_slice(digest.as_bytes());
+                }
+                (offset, chunks.len() as u32)
+            }
+            None => (0, 0),
+        };
+
+        let (link_offset, link_len) = match &fingerprint.link_target {
+            Some(link_target) => {
+                flags |= BINARY_FLAG_HAS_LINK_TARGET;
+                let offset = blob.len() as u64;
+                let bytes = link_target.as_bytes();
+                blob.extend_from_slice(bytes);
+                (offset, bytes.len() as u32)
+            }
+            None => (0, 0),
+        };
+
+        records.push(flags);
+        records.extend_from_slice(&size.to_le_bytes());
+        records.extend_from_slice(&mtime_secs.to_le_bytes());
+        records.extend_from_slice(&mtime_nanos.to_le_bytes());
+        records.extend_from_slice(&path_offset.to_le_bytes());
+        records.extend_from_slice(&path_len.to_le_bytes());
+        records.extend_from_slice(&chunks_offset.to_le_bytes());
+        records.extend_from_slice(&chunks_count.to_le_bytes());
+        records.extend_from_slice(&link_offset.to_le_bytes());
+        records.extend_from_slice(&link_len.to_le_bytes());
+    }
+
+    let root_offset = (BINARY_HEADER_SIZE + records.len()) as u64;
+
+    let mut payload = Vec::with_capacity(root_offset as usize + blob.len());
+    payload.extend_from_slice(BINARY_MAGIC);
+    payload.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+    payload.extend_from_slice(&(saved_state.fingerprints.len() as u32).to_le_bytes());
+    payload.extend_from_slice(&root_offset.to_le_bytes());
+    payload.extend_from_slice(&saved_state.saved_at.0.to_le_bytes());
+    payload.extend_from_slice(&saved_state.saved_at.1.to_le_bytes());
+    payload.extend_from_slice(&records);
+    payload.extend_from_slice(&blob);
+
+    Ok(payload)
+}
+
+/// Decodes the layout produced by `encode_binary_state`, failing with a
+/// reason string on any malformed or truncated input.
+fn decode_binary_state(payload: &[u8]) -> Result<SavedState, String> {
+    if payload.len() < BINARY_HEADER_SIZE || &payload[0..4] != BINARY_MAGIC {
+        return Err("missing binary state header".to_string());
+    }
+
+    let version = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+    if version != BINARY_VERSION {
+        return Err(format!("unsupported binary state version {}", version));
+    }
+
+    let entry_count = u32::from_le_bytes(payload[8..12].try_into().unwrap()) as usize;
+    let root_offset = u64::from_le_bytes(payload[12..20].try_into().unwrap()) as usize;
+    let saved_at_secs = u64::from_le_bytes(payload[20..28].try_into().unwrap());
+    let saved_at_nanos = u32::from_le_bytes(payload[28..32].try_into().unwrap());
+
+    let records_end = BINARY_HEADER_SIZE + entry_count * BINARY_RECORD_SIZE;
+    let records = payload.get(BINARY_HEADER_SIZE..records_end).ok_or("truncated record table")?;
+    let blob = payload.get(root_offset..).ok_or("root offset past end of file")?;
+
+    let mut fingerprints = Vec::with_capacity(entry_count);
+    for record in records.chunks_exact(BINARY_RECORD_SIZE) {
+        let flags = record[0];
+        let size = u64::from_le_bytes(record[1..9].try_into().unwrap());
+        let mtime_secs = u64::from_le_bytes(record[9..17].try_into().unwrap());
+        let mtime_nanos = u32::from_le_bytes(record[17..21].try_into().unwrap());
+        let path_offset = u64::from_le_bytes(record[21..29].try_into().unwrap()) as usize;
+        let path_len = u32::from_le_bytes(record[29..33].try_into().unwrap()) as usize;
+        let chunks_offset = u64::from_le_bytes(record[33..41].try_into().unwrap()) as usize;
+        let chunks_count = u32::from_le_bytes(record[41..45].try_into().unwrap()) as usize;
+        let link_offset = u64::from_le_bytes(record[45..53].try_into().unwrap()) as usize;
+        let link_len = u32::from_le_bytes(record[53..57].try_into().unwrap()) as usize;
+
+        let path_bytes = blob.get(path_offset..path_offset + path_len).ok_or("path bytes out of bounds")?;
+        let relative_path = PathBuf::from(String::from_utf8_lossy(path_bytes).into_owned());
+
+        let (size, mtime) = if flags & BINARY_FLAG_HAS_FILE_META != 0 {
+            (Some(size), Some((mtime_secs, mtime_nanos)))
+        } else {
+            (None, None)
+        };
+
+        let chunks = if flags & BINARY_FLAG_HAS_CHUNKS != 0 {
+            let mut chunk_hashes = Vec::with_capacity(chunks_count);
+            for i in 0..chunks_count {
+                let start = chunks_offset + i * 32;
+                let digest_bytes = blob.get(start..start + 32).ok_or("chunk digest out of bounds")?;
+                let digest: [u8; 32] = digest_bytes.try_into().unwrap();
+                chunk_hashes.push(blake3::Hash::from(digest).to_hex().to_string());
+            }
+            Some(chunk_hashes)
+        } else {
+            None
+        };
+
+        let link_target = if flags & BINARY_FLAG_HAS_LINK_TARGET != 0 {
+            let bytes = blob.get(link_offset..link_offset + link_len).ok_or("link target out of bounds")?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        } else {
+            None
+        };
+
+        fingerprints.push(Fingerprint {
+            relative_path,
+            is_dir: flags & BINARY_FLAG_IS_DIR != 0,
+            chunks,
+            size,
+            mtime,
+            link_target,
+        });
+    }
+
+    Ok(SavedState { saved_at: (saved_at_secs, saved_at_nanos), fingerprints })
+}
+
+/// Compiled set of gitignore-style glob patterns from `--ignore` flags and
+/// a `.difftoolignore` file at the scan root.
+struct IgnoreMatcher {
+    set: GlobSet,
+}
+
+impl IgnoreMatcher {
+    /// Returns true if `relative_path` (relative to the scan root) matches
+    /// any compiled ignore pattern.
+    fn is_match(&self, relative_path: &Path) -> bool {
+        self.set.is_match(relative_path)
+    }
+}
+
+/// Anchors a pattern the way `.gitignore` does: `/`-prefixed is rooted,
+/// `/`-containing is used as-is, otherwise it matches at any depth.
+fn anchor_ignore_pattern(pattern: &str) -> String {
+    if let Some(rooted) = pattern.strip_prefix('/') {
+        rooted.to_string()
+    } else if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    }
+}
+
+/// Builds the `IgnoreMatcher` for a scan, combining `--ignore` flags with
+/// any patterns found in a `.difftoolignore` file at `root`.
+fn build_ignore_matcher(root: &Path, args: &Args) -> IgnoreMatcher {
+    let mut builder = GlobSetBuilder::new();
+
+    let add_pattern = |builder: &mut GlobSetBuilder, pattern: &str| {
+        match Glob::new(&anchor_ignore_pattern(pattern)) {
+            Ok(glob) => { builder.add(glob); }
+            Err(e) => eprintln!("ERROR: Invalid ignore pattern '{}': {}", pattern, e),
+        }
+    };
+
+    for pattern in &args.ignore {
+        add_pattern(&mut builder, pattern);
+    }
+
+    if let Ok(contents) = fs::read_to_string(root.join(".difftoolignore")) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            add_pattern(&mut builder, line);
+        }
+    }
+
+    let set = builder.build().unwrap_or_else(|e| {
+        eprintln!("ERROR: Failed to compile ignore patterns: {}", e);
+        GlobSet::empty()
+    });
+
+    IgnoreMatcher { set }
 }
 
 /// Represents a file operation (create, delete, copy) needed to synchronize directories.
@@ -61,6 +380,25 @@ struct Fingerprint {
 struct DiffOperation {
     op: String,
     path: String,
+    /// Source path for a `"rename"` op; `None` for every other op.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+}
+
+/// Builds a capped Rayon thread pool for fingerprinting. Honors `--jobs`,
+/// defaulting to `min(available_parallelism, 16)`.
+fn build_thread_pool(args: &Args) -> rayon::ThreadPool {
+    let jobs = args.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(16)
+    });
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to build thread pool with {} jobs: {}", jobs, e);
+            std::process::exit(1);
+        })
 }
 
 #[cfg(feature = "ai_priority")]
@@ -131,34 +469,59 @@ fn load_model() -> CModule {
 /// Scans the given directory and returns a list of fingerprints representing
 /// the content and structure of the directory at the time of scanning.
 /// Applies optional AI-based prioritization for hashing if enabled.
-fn scan_directory(root: &Path, args: &Args) -> io::Result<Vec<Fingerprint>> {
+fn scan_directory(
+    root: &Path,
+    args: &Args,
+    matcher: &IgnoreMatcher,
+    reference: Option<(&HashMap<&PathBuf, &Fingerprint>, SystemTime)>,
+) -> io::Result<Vec<Fingerprint>> {
     let start = Instant::now();
 
     #[cfg(feature = "ai_priority")]
     let model = load_model();
 
-    let (prioritized_files, dirs) = collect_paths_with_priority(root, #[cfg(feature = "ai_priority")] &model);
-    let fingerprints = hash_files_to_fingerprints(&prioritized_files, root, &args);
+    let (prioritized_files, immediate_fingerprints, skipped) =
+        collect_paths_with_priority(root, matcher, #[cfg(feature = "ai_priority")] &model);
+    for (path, bad_type) in &skipped {
+        vprintln!(args.verbosity >= 1, "Skipped '{}' ({})", path.display(), bad_type.label());
+    }
+    let pool = build_thread_pool(args);
+    let fingerprints = pool.install(|| hash_files_to_fingerprints(&prioritized_files, root, &args, reference));
 
     let elapsed = start.elapsed();
     vprintln!(args.verbosity >= 1, "Scanned directory in {:.2?}", elapsed);
 
     let mut all = fingerprints;
-    all.extend(dirs);
+    all.extend(immediate_fingerprints);
     Ok(all)
 }
 
 
 /// Traverses a directory tree, identifying all files and directories,
-/// and assigns priority scores for sorting (AI-based if enabled).
+/// and assigns priority scores for sorting (AI-based if enabled). Symlinks
+/// are recorded as fingerprints carrying their link target; other special
+/// file types are routed into `skipped` instead.
 fn collect_paths_with_priority(
     root: &Path,
+    matcher: &IgnoreMatcher,
     #[cfg(feature = "ai_priority")] model: &CModule,
-) -> (Vec<(f32, PathBuf)>, Vec<Fingerprint>) {
+) -> (Vec<(f32, PathBuf)>, Vec<Fingerprint>, Vec<(PathBuf, BadType)>) {
     let mut prioritized_files = Vec::new();
-    let mut dirs = Vec::new();
+    let mut immediate_fingerprints = Vec::new();
+    let mut skipped = Vec::new();
 
-    for entry in WalkDir::new(root).into_iter() {
+    let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+        match entry.path().strip_prefix(root) {
+            Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+                !matcher.is_match(relative_path)
+            }
+            // Keep the root itself, and anything we fail to relativize (the
+            // fallback path below will log and skip it).
+            _ => true,
+        }
+    });
+
+    for entry in walker {
         let entry = match entry {
             Ok(e) => e,
             Err(e) => {
@@ -174,14 +537,18 @@ fn collect_paths_with_priority(
                 continue;
             }
         };
+        let file_type = entry.file_type();
 
-        if entry.file_type().is_dir() {
-            dirs.push(Fingerprint {
+        if file_type.is_dir() {
+            immediate_fingerprints.push(Fingerprint {
                 relative_path,
                 is_dir: true,
                 chunks: None,
+                size: None,
+                mtime: None,
+                link_target: None,
             });
-        } else if entry.file_type().is_file() {
+        } else if file_type.is_file() {
             #[cfg(feature = "ai_priority")]
             let score = predict_priority(model, &path, root);
 
@@ -189,17 +556,39 @@ fn collect_paths_with_priority(
             let score = 0.0;
 
             prioritized_files.push((score, path));
+        } else {
+            match classify_bad_type(file_type) {
+                BadType::Symlink => match fs::read_link(&path) {
+                    Ok(target) => immediate_fingerprints.push(Fingerprint {
+                        relative_path,
+                        is_dir: false,
+                        chunks: None,
+                        size: None,
+                        mtime: None,
+                        link_target: Some(target.to_string_lossy().into_owned()),
+                    }),
+                    Err(e) => eprintln!("ERROR: Failed to read symlink '{}': {:?}", path.display(), e),
+                },
+                bad_type => skipped.push((relative_path, bad_type)),
+            }
         }
     }
 
     prioritized_files.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-    (prioritized_files, dirs)
+    (prioritized_files, immediate_fingerprints, skipped)
 }
 
 
 /// Processes a list of prioritized file paths by computing their content hashes
-/// and producing corresponding Fingerprint structs.
-fn hash_files_to_fingerprints(prioritized_files: &[(f32, PathBuf)], root: &Path, args: &Args) -> Vec<Fingerprint> {
+/// and producing corresponding Fingerprint structs. A file whose size and mtime
+/// match the reference fingerprint (and isn't ambiguously recent) reuses the
+/// cached chunk hashes instead of being re-read and re-chunked.
+fn hash_files_to_fingerprints(
+    prioritized_files: &[(f32, PathBuf)],
+    root: &Path,
+    args: &Args,
+    reference: Option<(&HashMap<&PathBuf, &Fingerprint>, SystemTime)>,
+) -> Vec<Fingerprint> {
     use indicatif::{ProgressBar, ProgressStyle};
 
     let progress = if args.verbosity == 1 {
@@ -223,14 +612,47 @@ fn hash_files_to_fingerprints(prioritized_files: &[(f32, PathBuf)], root: &Path,
             }
         };
 
-        let chunks = match chunk_and_hash_file(path, args) {
-            Ok(c) => c,
+        let metadata = match fs::metadata(path) {
+            Ok(m) => m,
             Err(e) => {
-                eprintln!("ERROR: chunk_and_hash_file failed: {:?}", e);
+                eprintln!("ERROR: Failed to stat '{}': {:?}", path.display(), e);
                 if let Some(pb) = &progress { pb.inc(1); }
                 return None;
             }
         };
+        let size = metadata.len();
+        let mtime = system_time_to_parts(metadata.modified().unwrap_or(UNIX_EPOCH));
+
+        let cached_chunks = reference.and_then(|(reference_map, state_write_time)| {
+            let reference_fingerprint = reference_map.get(&relative_path)?;
+            if reference_fingerprint.is_dir
+                || reference_fingerprint.size != Some(size)
+                || reference_fingerprint.mtime != Some(mtime) {
+                return None;
+            }
+
+            // Ambiguous mtime: the file may have been touched in the same
+            // clock tick the reference state was written, so we can't trust
+            // the cached hashes.
+            let mtime_instant = UNIX_EPOCH + Duration::new(mtime.0, mtime.1);
+            if mtime_instant >= state_write_time {
+                return None;
+            }
+
+            reference_fingerprint.chunks.clone()
+        });
+
+        let chunks = match cached_chunks {
+            Some(chunks) => chunks,
+            None => match chunk_and_hash_file(path, args) {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("ERROR: chunk_and_hash_file failed: {:?}", e);
+                    if let Some(pb) = &progress { pb.inc(1); }
+                    return None;
+                }
+            },
+        };
 
         if let Some(pb) = &progress { pb.inc(1); }
 
@@ -238,6 +660,9 @@ fn hash_files_to_fingerprints(prioritized_files: &[(f32, PathBuf)], root: &Path,
             relative_path,
             is_dir: false,
             chunks: Some(chunks),
+            size: Some(size),
+            mtime: Some(mtime),
+            link_target: None,
         })
     }).collect();
 
@@ -252,30 +677,82 @@ fn hash_files_to_fingerprints(prioritized_files: &[(f32, PathBuf)], root: &Path,
 
 /// Compares two sets of directory fingerprints and produces a list of operations
 /// (create, delete, copy) needed to synchronize them.
-fn diff_states(reference_state: &Vec<Fingerprint>, current_state: &Vec<Fingerprint>, args: &Args) -> Vec<DiffOperation> {
+fn diff_states(reference_state: &Vec<Fingerprint>, current_state: &Vec<Fingerprint>, matcher: &IgnoreMatcher, args: &Args) -> Vec<DiffOperation> {
     let start = Instant::now();
 
+    // Paths may have been ignored after being recorded in a prior state (or
+    // the ignore patterns may have changed since); drop them here too so
+    // they never produce spurious create/delete operations. A path under an
+    // ignored directory counts as ignored, since traversal prunes it.
+    let is_ignored = |path: &Path| path.ancestors()
+        .any(|p| !p.as_os_str().is_empty() && matcher.is_match(p));
     let reference_map: HashMap<&PathBuf, &Fingerprint> = reference_state.iter()
+        .filter(|f| !is_ignored(f.relative_path.as_path()))
         .map(|f| (&f.relative_path, f)).collect();
     let current_map: HashMap<&PathBuf, &Fingerprint> = current_state.iter()
+        .filter(|f| !is_ignored(f.relative_path.as_path()))
         .map(|f| (&f.relative_path, f)).collect();
 
     let mut operations: Vec<DiffOperation> = Vec::new();
+    let mut deleted_paths: Vec<&PathBuf> = Vec::new();
+    let mut created_paths: Vec<&PathBuf> = Vec::new();
 
     for (path, reference_fingerprint) in &reference_map {
         match current_map.get(path) {
-            None => operations.push(DiffOperation { op: "delete".into(), path: path.display().to_string() }),
+            None => deleted_paths.push(*path),
             Some(current_fingerprint) => {
-                if reference_fingerprint.chunks != current_fingerprint.chunks {
-                    operations.push(DiffOperation { op: "copy".into(), path: path.display().to_string() });
+                if reference_fingerprint.chunks != current_fingerprint.chunks
+                    || reference_fingerprint.link_target != current_fingerprint.link_target {
+                    operations.push(DiffOperation { op: "copy".into(), path: path.display().to_string(), from: None });
                 }
             }
         }
     }
 
-    for (path, _) in &current_map {
+    for path in current_map.keys() {
         if !reference_map.contains_key(path) {
-            operations.push(DiffOperation { op: "create".into(), path: path.display().to_string() });
+            created_paths.push(*path);
+        }
+    }
+
+    // Pair deletes with creates that carry identical chunk content into a
+    // single "rename" op, so a moved file doesn't pay for a full recopy.
+    // Files that share no chunks (directories, symlinks) are never matched.
+    let mut creates_by_chunks: HashMap<&Vec<String>, Vec<&PathBuf>> = HashMap::new();
+    for path in &created_paths {
+        if let Some(chunks) = current_map[*path].chunks.as_ref() {
+            creates_by_chunks.entry(chunks).or_default().push(path);
+        }
+    }
+
+    let mut matched_creates: HashSet<&PathBuf> = HashSet::new();
+    for path in &deleted_paths {
+        let matched_path = reference_map[*path].chunks.as_ref().and_then(|chunks| {
+            let candidates = creates_by_chunks.get(chunks)?;
+            for candidate in candidates.iter() {
+                if !matched_creates.contains(*candidate) {
+                    return Some(*candidate);
+                }
+            }
+            None
+        });
+
+        match matched_path {
+            Some(new_path) => {
+                matched_creates.insert(new_path);
+                operations.push(DiffOperation {
+                    op: "rename".into(),
+                    path: new_path.display().to_string(),
+                    from: Some(path.display().to_string()),
+                });
+            }
+            None => operations.push(DiffOperation { op: "delete".into(), path: path.display().to_string(), from: None }),
+        }
+    }
+
+    for path in &created_paths {
+        if !matched_creates.contains(*path) {
+            operations.push(DiffOperation { op: "create".into(), path: path.display().to_string(), from: None });
         }
     }
 
@@ -295,7 +772,10 @@ fn output_operations(operations: &[DiffOperation], json_output_file: Option<Stri
     } else {
         vprintln!(verbosity >= 1, "--------------------------------------");
         for op in operations {
-            println!("{} `{}`", op.op, op.path);
+            match &op.from {
+                Some(from) => println!("{} `{}` -> `{}`", op.op, from, op.path),
+                None => println!("{} `{}`", op.op, op.path),
+            }
         }
         vprintln!(verbosity >= 1, "--------------------------------------");
     }
@@ -303,10 +783,73 @@ fn output_operations(operations: &[DiffOperation], json_output_file: Option<Stri
 }
 
 
-/// Serializes and saves the directory fingerprint state to a file.
-fn save_state(state: Vec<Fingerprint>, file_name: String, verbosity: u8) -> io::Result<()> {
-    let file = FileSystem::create(file_name)?;
-    serde_json::to_writer(file, &state)?;
+/// Works out the state file name and format from `--state`, `--format`, and
+/// the extension on `--state` itself.
+fn resolve_state_file(args: &Args) -> (String, StateFormat) {
+    let extension = Path::new(&args.state).extension().and_then(|e| e.to_str());
+
+    let format = args.format.unwrap_or_else(|| match extension {
+        Some("state") | Some("bin") => StateFormat::Binary,
+        _ => StateFormat::Json,
+    });
+
+    let file_name = if extension.is_some() {
+        args.state.clone()
+    } else {
+        match format {
+            StateFormat::Json => format!("{}.json", args.state),
+            StateFormat::Binary => format!("{}.state", args.state),
+        }
+    };
+
+    (file_name, format)
+}
+
+/// Reads back a saved state file, auto-detecting binary (zstd-wrapped) vs. json.
+fn load_saved_state(file_name: &str) -> io::Result<SavedState> {
+    let bytes = fs::read(file_name).map_err(|e| {
+        eprintln!("ERROR: Failed to open reference state file '{}': {}", file_name, e);
+        e
+    })?;
+
+    if let Ok(decompressed) = zstd::decode_all(&bytes[..]) {
+        // It was zstd-compressed, so it can only be our binary format - a
+        // failure here is a real corruption/version mismatch, not something
+        // JSON parsing of the (still-compressed) bytes could ever recover.
+        return decode_binary_state(&decompressed).map_err(|e| {
+            eprintln!("ERROR: '{}' looked like a binary state file but failed to parse: {}", file_name, e);
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        });
+    }
+
+    serde_json::from_slice(&bytes).map_err(|e| {
+        eprintln!("ERROR: Failed to parse reference state '{}': {}", file_name, e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+/// Serializes and saves the directory fingerprint state to a file, stamping
+/// it with the wall-clock time of the save so the next run can detect
+/// ambiguous mtimes. `format` selects plain JSON or the compact binary layout.
+fn save_state(state: Vec<Fingerprint>, file_name: String, format: StateFormat, verbosity: u8) -> io::Result<()> {
+    let saved_state = SavedState {
+        saved_at: system_time_to_parts(SystemTime::now()),
+        fingerprints: state,
+    };
+
+    match format {
+        StateFormat::Json => {
+            let file = FileSystem::create(file_name)?;
+            serde_json::to_writer(file, &saved_state)?;
+        }
+        StateFormat::Binary => {
+            let payload = encode_binary_state(&saved_state)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let compressed = zstd::encode_all(&payload[..], 0)?;
+            fs::write(file_name, compressed)?;
+        }
+    }
+
     vprintln!(verbosity >= 1, "Directory state saved.");
 
     Ok(())
@@ -322,43 +865,164 @@ fn main() -> io::Result<()> {
         std::process::exit(1);
     }
 
-    let file_name = format!("{}.json", args.state);
+    let (file_name, format) = resolve_state_file(&args);
+    let matcher = build_ignore_matcher(directory_path, &args);
 
     if fs::exists(&file_name)? {
         vprintln!(args.verbosity >= 1, "Deserializing reference state...");
         let start = Instant::now();
 
-        let file = FileSystem::open(&file_name).map_err(|e| {
-            eprintln!("ERROR: Failed to open reference state file '{}': {}", file_name, e);
-            e
-        })?;
-        let reference_state: Vec<Fingerprint> = serde_json::from_reader(file).map_err(|e| {
-            eprintln!("ERROR: Failed to parse reference state '{}': {}", file_name, e);
-            e
-        })?;
+        let saved_state = load_saved_state(&file_name)?;
         vprintln!(args.verbosity >= 1, "...done deserializing in {:.2?}", start.elapsed());
 
-        let current_state = match scan_directory(directory_path, &args) {
+        let reference_state = saved_state.fingerprints;
+        let state_write_time = UNIX_EPOCH + Duration::new(saved_state.saved_at.0, saved_state.saved_at.1);
+        let reference_map: HashMap<&PathBuf, &Fingerprint> = reference_state.iter()
+            .map(|f| (&f.relative_path, f)).collect();
+
+        let current_state = match scan_directory(directory_path, &args, &matcher, Some((&reference_map, state_write_time))) {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("ERROR: Failed to scan directory '{}': {}", directory_path.display(), e);
                 return Err(e);
             }
         };
-        let operations = diff_states(&reference_state, &current_state, &args);
+        let operations = diff_states(&reference_state, &current_state, &matcher, &args);
         output_operations(&operations, args.json, args.verbosity)?;
-        save_state(current_state, file_name, args.verbosity)?;
+        save_state(current_state, file_name, format, args.verbosity)?;
     } else {
         vprintln!(args.verbosity >= 1, "No reference state found, creating new state...");
-        let state = match scan_directory(directory_path, &args) {
+        let state = match scan_directory(directory_path, &args, &matcher, None) {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("ERROR: Failed to scan directory '{}': {}", directory_path.display(), e);
                 return Err(e);
             }
         };
-        save_state(state, file_name, args.verbosity)?;
+        save_state(state, file_name, format, args.verbosity)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_args() -> Args {
+        Args { state: "state".to_string(), target: ".".to_string(), json: None, verbosity: 0, ignore: Vec::new(), jobs: None, format: None }
+    }
+
+    fn fingerprint(relative_path: &str, chunks: Vec<&str>) -> Fingerprint {
+        Fingerprint {
+            relative_path: PathBuf::from(relative_path),
+            is_dir: false,
+            chunks: Some(chunks.into_iter().map(String::from).collect()),
+            size: Some(0),
+            mtime: Some((0, 0)),
+            link_target: None,
+        }
+    }
+
+    #[test]
+    fn hash_files_to_fingerprints_rehashes_on_ambiguous_mtime() {
+        let dir = std::env::temp_dir().join(format!("difftool_test_{}_{}", std::process::id(), line!()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("file.txt");
+        fs::write(&file_path, b"hello world").unwrap();
+
+        let metadata = fs::metadata(&file_path).unwrap();
+        let mtime = system_time_to_parts(metadata.modified().unwrap());
+
+        let relative_path = PathBuf::from("file.txt");
+        let stale = Fingerprint {
+            relative_path: relative_path.clone(),
+            is_dir: false,
+            chunks: Some(vec!["stale-hash-no-real-hasher-would-produce".to_string()]),
+            size: Some(metadata.len()),
+            mtime: Some(mtime),
+            link_target: None,
+        };
+        let reference_map: HashMap<&PathBuf, &Fingerprint> = [(&relative_path, &stale)].into_iter().collect();
+
+        // The reference state was saved at (or after) this file's mtime, so
+        // the mtime is ambiguous and the cached chunks must not be trusted.
+        let state_write_time = metadata.modified().unwrap();
+
+        let args = test_args();
+        let prioritized_files = vec![(0.0, file_path.clone())];
+        let fingerprints = hash_files_to_fingerprints(&prioritized_files, &dir, &args, Some((&reference_map, state_write_time)));
+
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(fingerprints.len(), 1);
+        assert_ne!(fingerprints[0].chunks, stale.chunks);
+    }
+
+    #[test]
+    fn anchor_ignore_pattern_matches_gitignore_anchoring_rules() {
+        assert_eq!(anchor_ignore_pattern("/build"), "build");
+        assert_eq!(anchor_ignore_pattern("src/generated"), "src/generated");
+        assert_eq!(anchor_ignore_pattern("*.log"), "**/*.log");
+    }
+
+    #[test]
+    fn diff_states_pairs_a_delete_and_create_with_identical_chunks_into_a_rename() {
+        let args = test_args();
+        let matcher = build_ignore_matcher(Path::new("."), &args);
+        let reference = vec![fingerprint("old.txt", vec!["h1", "h2"])];
+        let current = vec![fingerprint("new.txt", vec!["h1", "h2"])];
+
+        let ops = diff_states(&reference, &current, &matcher, &args);
+
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].op, "rename");
+        assert_eq!(ops[0].path, "new.txt");
+        assert_eq!(ops[0].from.as_deref(), Some("old.txt"));
+    }
+
+    #[test]
+    fn diff_states_flags_a_copy_when_content_changes_past_the_leading_chunk() {
+        // Regression test: a file's partial signature (leading bytes) can
+        // stay identical while later content changes, so `chunks` must always
+        // be the full content chunk list or this edit goes undetected.
+        let args = test_args();
+        let matcher = build_ignore_matcher(Path::new("."), &args);
+        let reference = vec![fingerprint("big.log", vec!["h1"])];
+        let current = vec![fingerprint("big.log", vec!["h1", "h2"])];
+
+        let ops = diff_states(&reference, &current, &matcher, &args);
+
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].op, "copy");
+        assert_eq!(ops[0].path, "big.log");
+    }
+
+    #[test]
+    fn binary_state_round_trips_through_encode_and_decode() {
+        let h1 = blake3::hash(b"one").to_hex().to_string();
+        let h2 = blake3::hash(b"two").to_hex().to_string();
+        let saved = SavedState {
+            saved_at: (1_700_000_000, 42),
+            fingerprints: vec![
+                fingerprint("file.txt", vec![&h1, &h2]),
+                Fingerprint { relative_path: PathBuf::from("dir"), is_dir: true, chunks: None, size: None, mtime: None, link_target: None },
+                Fingerprint { relative_path: PathBuf::from("link"), is_dir: false, chunks: None, size: None, mtime: None, link_target: Some("target.txt".to_string()) },
+            ],
+        };
+
+        let encoded = encode_binary_state(&saved).expect("encode should succeed");
+        let decoded = decode_binary_state(&encoded).expect("decode should succeed");
+
+        assert_eq!(decoded.saved_at, saved.saved_at);
+        assert_eq!(decoded.fingerprints.len(), saved.fingerprints.len());
+        for (expected, actual) in saved.fingerprints.iter().zip(decoded.fingerprints.iter()) {
+            assert_eq!(actual.relative_path, expected.relative_path);
+            assert_eq!(actual.is_dir, expected.is_dir);
+            assert_eq!(actual.chunks, expected.chunks);
+            assert_eq!(actual.size, expected.size);
+            assert_eq!(actual.mtime, expected.mtime);
+            assert_eq!(actual.link_target, expected.link_target);
+        }
+    }
+}